@@ -0,0 +1,424 @@
+// Command line definition. Flag values are validated while parsing, defaults coming from the
+// environment or the config file are applied in main, after the arguments have been parsed.
+
+use clap::builder::RangedU64ValueParser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use whisper_connector::{
+    AudioFormat, ResponseFormat, DEFAULT_CHANNELS, DEFAULT_REQUEST_TIMEOUT_SECS, DEFAULT_SAMPLE_RATE,
+};
+
+// Parallel uploads for transcribe-dir, unless --concurrency says otherwise.
+const DEFAULT_CONCURRENCY: usize = 4;
+
+#[derive(Parser)]
+#[command(
+    name = "whisper_connector",
+    about = "Records audio with ffmpeg and transcribes it with the OpenAI Whisper API.",
+    disable_version_flag = true
+)]
+pub struct Cli {
+    /// Print debug logs of this crate to stderr, RUST_LOG is respected as well
+    #[arg(long, global = true)]
+    pub verbose: bool,
+
+    /// Only print the result and errors, without the recording indicator, the device picker and log warnings
+    #[arg(long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// ffmpeg binary to use instead of FFMPEG_PATH or the one on PATH
+    #[arg(long, global = true, value_name = "PATH")]
+    pub ffmpeg_path: Option<String>,
+
+    /// Load environment variables from this file instead of .env in the current directory, set ones are kept
+    #[arg(long, global = true, value_name = "PATH")]
+    pub env_file: Option<std::path::PathBuf>,
+
+    /// Print the version of whisper_connector and ffmpeg
+    #[arg(short = 'V', long)]
+    pub version: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Record from an audio device until the stop key is pressed and transcribe it
+    Transcribe {
+        /// Deprecated, use --language. A lone argument that isn't a language is taken as the device
+        language: Option<String>,
+        /// Device name or its number as printed by `devices`, defaults to the last used one
+        device: Option<String>,
+        /// ISO-639-1 code (e.g. en, pl), defaults to `language` from the config file or auto
+        #[arg(short = 'l', long = "language", value_name = "CODE")]
+        language_flag: Option<String>,
+        #[command(flatten)]
+        recording: RecordingArgs,
+        #[command(flatten)]
+        transcription: TranscriptionArgs,
+    },
+    /// Record from an audio device and translate the speech into English
+    Translate {
+        /// Device name or its number as printed by `devices`, defaults to the last used one
+        device: Option<String>,
+        #[command(flatten)]
+        recording: RecordingArgs,
+        #[command(flatten)]
+        transcription: TranscriptionArgs,
+    },
+    /// Transcribe an existing audio file
+    TranscribeFile {
+        /// Path of the file, - reads the audio from stdin
+        path: String,
+        /// Deprecated, use --language
+        language: Option<String>,
+        /// ISO-639-1 code (e.g. en, pl), defaults to `language` from the config file or auto
+        #[arg(short = 'l', long = "language", value_name = "CODE", conflicts_with = "language")]
+        language_flag: Option<String>,
+        /// Format of the audio piped into stdin, defaults to `format` from the config file or mp3
+        #[arg(long, value_parser = parse_audio_format)]
+        format: Option<AudioFormat>,
+        /// Convert the file with ffmpeg first, for formats Whisper doesn't accept (e.g. amr, opus, video files)
+        #[arg(long)]
+        transcode: bool,
+        #[command(flatten)]
+        transcription: TranscriptionArgs,
+    },
+    /// Transcribe every audio file in a directory
+    TranscribeDir {
+        dir: String,
+        /// Deprecated, use --language
+        language: Option<String>,
+        /// ISO-639-1 code (e.g. en, pl), defaults to `language` from the config file or auto
+        #[arg(short = 'l', long = "language", value_name = "CODE", conflicts_with = "language")]
+        language_flag: Option<String>,
+        /// Number of parallel uploads, keep it low to stay below the API rate limits
+        #[arg(long, default_value_t = DEFAULT_CONCURRENCY, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+        concurrency: usize,
+        /// Send at most this many requests per minute, spread evenly, retries after a 429 included
+        #[arg(long, value_name = "N", value_parser = RangedU64ValueParser::<u32>::new().range(1..))]
+        rpm: Option<u32>,
+        #[command(flatten)]
+        transcription: TranscriptionArgs,
+    },
+    /// List the audio input devices
+    Devices {
+        /// Only list devices capturing the system audio
+        #[arg(long)]
+        loopback: bool,
+        /// Windows only: also print the dshow alternative name of each device, for --device-alt
+        #[arg(long, conflicts_with = "loopback")]
+        alternative_names: bool,
+        /// Print the devices as a JSON array of {"index", "name"} objects
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the supported language codes
+    #[command(alias = "list-languages")]
+    Languages,
+    /// Check ffmpeg, the audio devices, the API key and the connection to the API
+    Doctor {
+        #[command(flatten)]
+        connection: ConnectionArgs,
+    },
+    /// Print the completion script for a shell, e.g. `completions bash > /etc/bash_completion.d/whisper_connector`
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Print the version of whisper_connector and ffmpeg
+    Version,
+    /// Started by --clipboard on Linux, serves the text read from stdin until something else is copied
+    #[command(hide = true)]
+    ClipboardDaemon,
+}
+
+// How the API is reached and authenticated, shared by the transcribing commands and doctor.
+#[derive(Args)]
+pub struct ConnectionArgs {
+    /// Read the API key from this file instead of OPENAI_AUTH_KEY
+    #[arg(long, value_name = "PATH")]
+    pub api_key_file: Option<String>,
+
+    /// Overrides HTTPS_PROXY and HTTP_PROXY
+    #[arg(long, value_name = "URL")]
+    pub proxy: Option<String>,
+
+    /// Send the requests to Azure OpenAI, set up with AZURE_OPENAI_ENDPOINT, AZURE_DEPLOYMENT and AZURE_API_VERSION
+    #[arg(long)]
+    pub azure: bool,
+
+    /// Skip TLS certificate verification, only for local test servers with self-signed certificates
+    #[arg(long)]
+    pub insecure: bool,
+}
+
+#[derive(Args)]
+pub struct TranscriptionArgs {
+    /// Print the result as JSON, errors are printed as {"error": ...} then
+    #[arg(long)]
+    pub json: bool,
+
+    /// Request segment and word timestamps
+    #[arg(long)]
+    pub timestamps: bool,
+
+    /// Copy the transcription to the clipboard
+    #[arg(long)]
+    pub clipboard: bool,
+
+    /// Exit with an error when nothing was recognized, the (empty) output is still written
+    #[arg(long)]
+    pub fail_on_empty: bool,
+
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+
+    /// Write the transcription to this file instead of stdout
+    #[arg(long, value_name = "PATH")]
+    pub output_file: Option<String>,
+
+    /// Pipe the output through this shell command and print (or write) what it prints instead
+    #[arg(long, value_name = "CMD", allow_hyphen_values = true)]
+    pub post_command: Option<String>,
+
+    /// Text guiding the spelling and style, e.g. names or jargon
+    #[arg(long, value_name = "TEXT", allow_hyphen_values = true)]
+    pub prompt: Option<String>,
+
+    /// Read the prompt from this file, e.g. a glossary. Can be repeated and combined with --prompt
+    #[arg(long, value_name = "PATH")]
+    pub prompt_file: Vec<String>,
+
+    /// Limit for a single request in seconds
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        env = "WHISPER_TIMEOUT_SECS",
+        default_value_t = DEFAULT_REQUEST_TIMEOUT_SECS,
+        value_parser = RangedU64ValueParser::<u64>::new().range(1..)
+    )]
+    pub timeout: u64,
+
+    /// srt and vtt require --timestamps
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub output_format: OutputFormat,
+
+    /// OpenAI organization ID the usage is billed to
+    #[arg(long, value_name = "ID", env = "OPENAI_ORG_ID")]
+    pub organization: Option<String>,
+
+    /// OpenAI project ID the usage is billed to
+    #[arg(long, value_name = "ID", env = "OPENAI_PROJECT_ID")]
+    pub project: Option<String>,
+
+    /// Content type of the uploaded file, e.g. audio/x-wav, derived from the extension by default
+    #[arg(long, value_name = "TYPE")]
+    pub mime: Option<String>,
+
+    /// Send the file with a Content-Length instead of streaming it, for servers that mishandle chunked uploads
+    #[arg(long)]
+    pub buffered_upload: bool,
+
+    /// Print the raw response body of the API to stderr, e.g. to debug a compatible server
+    #[arg(long)]
+    pub raw: bool,
+
+    /// What the API is asked to respond with, text can't be combined with --timestamps
+    #[arg(long, value_name = "json|text", default_value = "json", value_parser = parse_response_format)]
+    pub response_format: ResponseFormat,
+
+    /// Comma separated codes, an auto detected language outside of them is transcribed again as the first one
+    #[arg(long, value_name = "CODES", value_delimiter = ',', value_parser = parse_language_code)]
+    pub allowed_languages: Vec<String>,
+
+    /// Defaults to `model` from the config file or whisper-1
+    #[arg(long, value_name = "NAME")]
+    pub model: Option<String>,
+
+    /// Sampling temperature between 0.0 and 1.0
+    #[arg(long, value_parser = parse_temperature)]
+    pub temperature: Option<f32>,
+
+    /// Leave out segments with a lower average log probability, e.g. -1.0, to drop made up text
+    #[arg(long, value_name = "LOGPROB", allow_hyphen_values = true, value_parser = parse_min_logprob)]
+    pub min_logprob: Option<f64>,
+
+    /// Leave out segments with a higher no-speech probability (0.0 to 1.0), e.g. 0.6
+    #[arg(long, value_name = "PROB", value_parser = parse_probability)]
+    pub max_no_speech: Option<f64>,
+}
+
+#[derive(Args)]
+pub struct RecordingArgs {
+    /// Stop recording after this many seconds
+    #[arg(long, value_name = "SECONDS", value_parser = RangedU64ValueParser::<u64>::new().range(1..))]
+    pub max_duration: Option<u64>,
+
+    /// mp3, wav, m4a or flac, defaults to `format` from the config file or mp3. wav is recommended, it needs no encoder and stops quickest. mp3 stays the default because wav reaches the 25MB upload limit after about 13 minutes
+    #[arg(long, value_parser = parse_audio_format)]
+    pub format: Option<AudioFormat>,
+
+    /// Lower sample rates reduce the upload size, Whisper resamples to 16kHz anyway
+    #[arg(long, value_name = "HZ", default_value_t = DEFAULT_SAMPLE_RATE, value_parser = RangedU64ValueParser::<u32>::new().range(8000..=192000))]
+    pub sample_rate: u32,
+
+    #[arg(long, default_value_t = DEFAULT_CHANNELS, value_parser = RangedU64ValueParser::<u32>::new().range(1..=8))]
+    pub channels: u32,
+
+    /// enter, space or a single character
+    #[arg(long, value_name = "KEY", default_value = "enter", value_parser = parse_stop_key)]
+    pub stop_key: u8,
+
+    /// Record and keep the file without uploading it, e.g. to test the microphone
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Filter out background noise before uploading
+    #[arg(long)]
+    pub denoise: bool,
+
+    /// Custom ffmpeg filter chain, replaces the --denoise one and is passed to ffmpeg unchecked
+    #[arg(long, value_name = "FILTERS", allow_hyphen_values = true)]
+    pub af: Option<String>,
+
+    /// Show the microphone level while recording, to spot a muted or dead microphone early
+    #[arg(long)]
+    pub meter: bool,
+
+    /// Cut leading and trailing silence before uploading
+    #[arg(long)]
+    pub trim_silence: bool,
+
+    /// Raw ffmpeg arguments placed before the capture input, split on whitespace
+    #[arg(long, value_name = "ARGS", allow_hyphen_values = true)]
+    pub ffmpeg_input_args: Option<String>,
+
+    /// Record to this file and keep it, the format follows its extension unless --format is given
+    #[arg(long, value_name = "PATH")]
+    pub keep_recording: Option<String>,
+
+    /// Allow --keep-recording to replace an existing file
+    #[arg(long, requires = "keep_recording")]
+    pub overwrite: bool,
+
+    /// Keep the temporary recording when the upload fails and print its path, e.g. to retry it with transcribe-file
+    #[arg(long)]
+    pub keep_on_error: bool,
+
+    /// Start recording sooner by not checking a device name against the device list first
+    #[arg(long)]
+    pub no_validate_device: bool,
+
+    /// Windows only: open the device by its dshow alternative name, see `devices --alternative-names`
+    #[arg(long, value_name = "ALT_NAME", conflicts_with = "device")]
+    pub device_alt: Option<String>,
+
+    /// Record the system audio (e.g. a meeting) instead of a microphone
+    #[arg(long)]
+    pub loopback: bool,
+
+    /// Record only while a key is held down
+    #[arg(long)]
+    pub hold: bool,
+
+    /// Start the next recording right after printing a result, until Ctrl-C or stdin is closed
+    #[arg(long, conflicts_with_all = ["output_file", "fail_on_empty", "keep_recording"])]
+    pub repeat: bool,
+
+    /// Transcribe the recording in chunks while it is still running
+    #[arg(long)]
+    pub chunked: bool,
+
+    /// Save the chunks under this session name as they are transcribed and continue after the ones saved before
+    #[arg(long, value_name = "SESSION", conflicts_with = "repeat", value_parser = parse_session_name)]
+    pub resume: Option<String>,
+
+    /// Length of a chunk, turns --chunked on as well
+    #[arg(long, value_name = "SECONDS", value_parser = RangedU64ValueParser::<u64>::new().range(1..))]
+    pub chunk_secs: Option<u64>,
+
+    /// Print an estimated cost of the transcription
+    #[arg(long)]
+    pub cost: bool,
+
+    /// Price per minute in USD, turns --cost on as well
+    #[arg(long, value_name = "USD", value_parser = parse_rate)]
+    pub rate: Option<f64>,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Srt,
+    Vtt,
+}
+
+fn parse_audio_format(name: &str) -> Result<AudioFormat, String> {
+    return match AudioFormat::from_name(name) {
+        Some(v) => Ok(v),
+        None => Err("supported formats: 'mp3', 'wav', 'm4a', 'flac'".to_string()),
+    };
+}
+
+fn parse_response_format(name: &str) -> Result<ResponseFormat, String> {
+    return match ResponseFormat::from_name(name) {
+        Some(v) => Ok(v),
+        None => Err("supported response formats: 'json', 'text'".to_string()),
+    };
+}
+
+// Only single byte keys can be matched against stdin, so anything but ASCII is rejected.
+fn parse_stop_key(name: &str) -> Result<u8, String> {
+    return match name.to_lowercase().as_str() {
+        "enter" => Ok(b'\n'),
+        "space" => Ok(b' '),
+        _ if name.len() == 1 && name.is_ascii() => Ok(name.as_bytes()[0]),
+        _ => Err("expected 'enter', 'space' or a single character".to_string()),
+    };
+}
+
+fn parse_language_code(code: &str) -> Result<String, String> {
+    if !whisper_connector::is_supported_language(code) {
+        return Err("expected an ISO-639-1 code supported by Whisper, see `languages`".to_string());
+    }
+
+    return Ok(code.to_string());
+}
+
+fn parse_temperature(value: &str) -> Result<f32, String> {
+    return match value.parse::<f32>() {
+        Ok(v) if (0.0..=1.0).contains(&v) => Ok(v),
+        _ => Err("expected a number between 0.0 and 1.0".to_string()),
+    };
+}
+
+fn parse_rate(value: &str) -> Result<f64, String> {
+    return match value.parse::<f64>() {
+        Ok(v) if v >= 0.0 && v.is_finite() => Ok(v),
+        _ => Err("expected a non-negative price per minute".to_string()),
+    };
+}
+
+fn parse_min_logprob(value: &str) -> Result<f64, String> {
+    return match value.parse::<f64>() {
+        Ok(v) if v <= 0.0 && v.is_finite() => Ok(v),
+        _ => Err("expected a log probability of 0.0 or less".to_string()),
+    };
+}
+
+fn parse_probability(value: &str) -> Result<f64, String> {
+    return match value.parse::<f64>() {
+        Ok(v) if (0.0..=1.0).contains(&v) => Ok(v),
+        _ => Err("expected a number between 0.0 and 1.0".to_string()),
+    };
+}
+
+// Used as a file name, so anything that could point outside of the sessions directory is rejected.
+fn parse_session_name(name: &str) -> Result<String, String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err("expected letters, digits, '-' or '_'".to_string());
+    }
+
+    return Ok(name.to_string());
+}
@@ -0,0 +1,41 @@
+// Defaults read from config.toml in the platform config directory, e.g.
+// ~/.config/whisper_connector/config.toml or %APPDATA%\GrupaDomanscy\whisper_connector\config\config.toml.
+//
+// Precedence: command line arguments > environment variables > .env file > config file > built-in defaults.
+
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    pub language: Option<String>,
+    pub default_device: Option<String>,
+    pub format: Option<String>,
+    pub model: Option<String>,
+    pub base_url: Option<String>,
+    /// In USD, used for the --cost estimate.
+    pub cost_per_minute: Option<f64>,
+}
+
+pub fn get_config_path() -> Option<std::path::PathBuf> {
+    let project_dirs = directories::ProjectDirs::from("", "GrupaDomanscy", "whisper_connector")?;
+
+    return Some(project_dirs.config_dir().join("config.toml"));
+}
+
+// A missing config file is not an error, built-in defaults are used then.
+pub fn load_config() -> Result<Config, String> {
+    let config_path = match get_config_path() {
+        Some(v) => v,
+        None => return Ok(Config::default()),
+    };
+
+    let contents = match std::fs::read_to_string(&config_path) {
+        Ok(v) => v,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(format!("Failed to read config file \"{}\": {e}", config_path.display())),
+    };
+
+    return match toml::from_str(&contents) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(format!("Invalid config file \"{}\": {e}", config_path.display())),
+    };
+}
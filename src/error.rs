@@ -0,0 +1,111 @@
+use std::io;
+
+#[derive(Debug)]
+pub enum WhisperError {
+    /// ffmpeg could not be started at all.
+    FfmpegSpawn(io::Error),
+    /// ffmpeg is not installed or not on PATH.
+    FfmpegNotFound,
+    /// ffmpeg was started, but failed or printed something we couldn't make sense of.
+    Ffmpeg(String),
+    DeviceNotFound(String),
+    /// The recorded file is too small to contain any audio.
+    EmptyRecording,
+    /// The recording was discarded through `RecordingOptions::abort_token`.
+    Terminated,
+    /// ffmpeg exited with this status without writing the recording at all.
+    NoAudioProduced(String),
+    /// Path of the audio file that doesn't exist or isn't a regular file.
+    FileNotFound(String),
+    /// Path of the file with an extension Whisper doesn't accept.
+    UnsupportedFileType(String),
+    /// Size in bytes of a file over the upload limit of the API.
+    FileTooLarge(u64),
+    /// Name of the OS, see `std::env::consts::OS`.
+    UnsupportedPlatform(String),
+    Http(reqwest::Error),
+    /// HTTP status, the error message returned by the API and the X-Request-Id the request was sent with.
+    Api(u16, String, String),
+    /// Proxy URL that couldn't be parsed, along with the reason.
+    InvalidProxy(String),
+    /// Limit in seconds that has been exceeded.
+    Timeout(u64),
+    Parse(serde_json::Error),
+    Io(io::Error),
+    /// Path of the recording kept by `RecordingOptions::keep_on_error`, along with the error it failed with.
+    RecordingKept(String, Box<WhisperError>),
+}
+
+impl std::fmt::Display for WhisperError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            WhisperError::FfmpegSpawn(e) => write!(f, "could not spawn ffmpeg process: {e}"),
+            WhisperError::FfmpegNotFound => write!(
+                f,
+                "ffmpeg was not found. Install it (https://ffmpeg.org/download.html) and make sure it is on your PATH."
+            ),
+            WhisperError::Ffmpeg(message) => write!(f, "{message}"),
+            WhisperError::DeviceNotFound(device) => write!(f, "Audio device \"{device}\" does not exist."),
+            WhisperError::EmptyRecording => write!(f, "recorded audio sample was empty; check your microphone"),
+            WhisperError::Terminated => write!(f, "terminated, the recording was discarded"),
+            WhisperError::NoAudioProduced(status) => write!(
+                f,
+                "ffmpeg finished ({status}) but no audio file was produced; the device may not support capture"
+            ),
+            WhisperError::FileNotFound(path) => write!(f, "Audio file \"{path}\" does not exist."),
+            WhisperError::UnsupportedFileType(path) => write!(
+                f,
+                "Unsupported audio file \"{path}\", supported types: flac, m4a, mp3, mp4, mpeg, mpga, ogg, wav, webm."
+            ),
+            WhisperError::FileTooLarge(bytes) => write!(
+                f,
+                "Audio file is {:.1}MB, the API accepts at most 25MB. Record shorter parts with --max-duration or compress it, e.g. with --format mp3, a lower --sample-rate or --transcode.",
+                *bytes as f64 / (1024.0 * 1024.0)
+            ),
+            WhisperError::UnsupportedPlatform(os) => write!(f, "recording audio is not supported on {os}"),
+            WhisperError::Http(e) => write!(f, "{e}"),
+            WhisperError::Api(status, message, request_id) if message.is_empty() => {
+                write!(f, "API request failed with status {status} (request id {request_id})")
+            },
+            WhisperError::Api(status, message, request_id) => {
+                write!(f, "API request failed with status {status} (request id {request_id}): {message}")
+            },
+            WhisperError::InvalidProxy(message) => write!(f, "invalid proxy URL {message}"),
+            WhisperError::Timeout(secs) => write!(f, "Request to the API timed out after {secs}s."),
+            WhisperError::Parse(e) => write!(f, "could not parse API response: {e}"),
+            WhisperError::Io(e) => write!(f, "{e}"),
+            WhisperError::RecordingKept(path, e) => write!(f, "{e}\nThe recording was kept at \"{path}\", retry with `transcribe-file`."),
+        };
+    }
+}
+
+impl std::error::Error for WhisperError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        return match self {
+            WhisperError::FfmpegSpawn(e) => Some(e),
+            WhisperError::Http(e) => Some(e),
+            WhisperError::Parse(e) => Some(e),
+            WhisperError::Io(e) => Some(e),
+            WhisperError::RecordingKept(_, e) => Some(e.as_ref()),
+            _ => None,
+        };
+    }
+}
+
+impl From<reqwest::Error> for WhisperError {
+    fn from(err: reqwest::Error) -> WhisperError {
+        return WhisperError::Http(err);
+    }
+}
+
+impl From<serde_json::Error> for WhisperError {
+    fn from(err: serde_json::Error) -> WhisperError {
+        return WhisperError::Parse(err);
+    }
+}
+
+impl From<io::Error> for WhisperError {
+    fn from(err: io::Error) -> WhisperError {
+        return WhisperError::Io(err);
+    }
+}
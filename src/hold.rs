@@ -0,0 +1,105 @@
+// Push-to-talk support: recording runs only while a key is held down.
+//
+// Key release events are reported natively on Windows and by terminals supporting the
+// kitty keyboard protocol. Everywhere else the release is detected by the key repeat
+// events stopping for a moment.
+
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use futures::StreamExt;
+
+use crate::WhisperError;
+
+// Longer than the usual initial key repeat delay (~500ms), so that holding a key isn't mistaken for a release.
+const REPEAT_TIMEOUT_MS: u64 = 600;
+
+pub enum HoldOutcome {
+    Key(KeyCode),
+    Cancelled,
+}
+
+// Keeps the terminal in raw mode for as long as it is alive. Dropping it restores cooked mode,
+// which covers every exit path, including errors and Ctrl-C.
+pub struct RawModeGuard {
+    release_events_supported: bool,
+    keyboard_enhancement_pushed: bool,
+}
+
+impl RawModeGuard {
+    pub fn enable() -> Result<RawModeGuard, WhisperError> {
+        crossterm::terminal::enable_raw_mode()?;
+
+        let mut guard = RawModeGuard {
+            release_events_supported: cfg!(target_os = "windows"),
+            keyboard_enhancement_pushed: false,
+        };
+
+        if !guard.release_events_supported && crossterm::terminal::supports_keyboard_enhancement().unwrap_or(false) {
+            crossterm::execute!(
+                std::io::stderr(),
+                crossterm::event::PushKeyboardEnhancementFlags(
+                    crossterm::event::KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                )
+            )?;
+
+            guard.release_events_supported = true;
+            guard.keyboard_enhancement_pushed = true;
+        }
+
+        return Ok(guard);
+    }
+}
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        if self.keyboard_enhancement_pushed {
+            let _ = crossterm::execute!(std::io::stderr(), crossterm::event::PopKeyboardEnhancementFlags);
+        }
+
+        let _ = crossterm::terminal::disable_raw_mode();
+    }
+}
+
+// Ctrl-C doesn't raise SIGINT in raw mode, it arrives as a regular key event instead.
+fn is_ctrl_c(key: &KeyEvent) -> bool {
+    return key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+}
+
+pub async fn wait_for_key_press(events: &mut EventStream) -> HoldOutcome {
+    while let Some(event) = events.next().await {
+        if let Ok(Event::Key(key)) = event {
+            if is_ctrl_c(&key) {
+                return HoldOutcome::Cancelled;
+            }
+
+            if key.kind == KeyEventKind::Press {
+                return HoldOutcome::Key(key.code);
+            }
+        }
+    }
+
+    return HoldOutcome::Cancelled;
+}
+
+pub async fn wait_for_key_release(events: &mut EventStream, guard: &RawModeGuard, code: KeyCode) -> HoldOutcome {
+    loop {
+        let event = tokio::time::timeout(std::time::Duration::from_millis(REPEAT_TIMEOUT_MS), events.next()).await;
+
+        let event = match event {
+            Ok(Some(Ok(v))) => v,
+            Ok(Some(Err(_))) | Ok(None) => return HoldOutcome::Key(code),
+            // No repeat events for a while, without release events that means the key was let go.
+            Err(_) if !guard.release_events_supported => return HoldOutcome::Key(code),
+            Err(_) => continue,
+        };
+
+        if let Event::Key(key) = event {
+            if is_ctrl_c(&key) {
+                return HoldOutcome::Cancelled;
+            }
+
+            if key.code == code && key.kind == KeyEventKind::Release {
+                return HoldOutcome::Key(code);
+            }
+        }
+    }
+}
@@ -1,287 +1,1574 @@
-use std::{io::BufRead, process::{exit, Stdio}};
-use rand::Rng;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, Stdout};
-use tokio_util::codec::{BytesCodec, FramedRead};
+// Functions end with an explicit return throughout this crate.
+#![allow(clippy::needless_return)]
 
-#[derive(serde::Deserialize, serde::Serialize)]
-struct SimpleOpenAIResponse {
+use std::io::IsTerminal;
+use std::process::exit;
+use clap::{CommandFactory, FromArgMatches};
+use futures::StreamExt;
+use whisper_connector::{
+    AudioFormat, AzureDeployment, RateLimiter, RecordingOptions, ResponseFormat, Segment, Transcription, TranscriptionOptions, WhisperError, Word,
+    AUTO_LANGUAGE, DEFAULT_AZURE_API_VERSION, DEFAULT_CHUNK_SECS, DEFAULT_MODEL, DEFAULT_OPENAI_BASE_URL, DENOISE_AUDIO_FILTER,
+};
+
+use crate::cli::{Cli, Command, ConnectionArgs, OutputFormat, RecordingArgs, TranscriptionArgs};
+
+mod cli;
+mod config;
+mod state;
+
+// OpenAI's price for whisper-1 in USD, other models and servers may differ, hence --rate.
+const DEFAULT_COST_PER_MINUTE: f64 = 0.006;
+
+// Whisper only considers the last 224 tokens of the prompt. Without a tokenizer at hand that's
+// estimated at 4 characters per token.
+const MAX_PROMPT_CHARS: usize = 224 * 4;
+
+const EMPTY_TRANSCRIPTION_MESSAGE: &str = "Transcription is empty, no speech was recognized.";
+
+// Printed after every --repeat result in text mode.
+const REPEAT_SEPARATOR: &str = "---";
+
+// Exit codes, listed in the --help output as well.
+const EXIT_FAILURE: i32 = 1;
+// ffmpeg, audio devices, the config file or the proxy.
+const EXIT_SETUP: i32 = 2;
+// Missing or rejected API key.
+const EXIT_AUTH: i32 = 3;
+// Connection problems, timeouts, rate limits and server errors.
+const EXIT_NETWORK: i32 = 4;
+// Nothing was recorded, or nothing recognized with --fail-on-empty.
+const EXIT_EMPTY: i32 = 5;
+// 128 + SIGTERM, the same as if the signal had killed the process.
+const EXIT_TERMINATED: i32 = 143;
+
+// How long the command gets to wind down after SIGTERM before the process exits anyway.
+const SIGTERM_GRACE_SECS: u64 = 2;
+
+const LOOPBACK_HINT: &str = "No loopback capable devices found. On Windows enable \"Stereo Mix\" in the sound settings or install a virtual audio cable, on macOS install e.g. BlackHole.";
+
+#[derive(serde::Serialize)]
+struct JsonTranscriptionOutput {
     text: String,
+    // null when Whisper detected the language on its own.
+    language: Option<String>,
+    // What Whisper detected in that case, e.g. "polish".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detected_language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    segments: Option<Vec<Segment>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    words: Option<Vec<Word>>,
+    // Path of the kept recording with --dry-run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recording: Option<String>,
 }
 
-fn error_to_string(err: impl std::error::Error) -> String {
-    return format!("{err}");
+#[derive(serde::Serialize)]
+struct JsonDevice {
+    index: usize,
+    name: String,
+    // Only looked up with --alternative-names.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alternative_name: Option<String>,
 }
 
-async fn get_audio_devices() -> Result<Vec<String>, String> {
-    let executed_cmd = tokio::process::Command::new("ffmpeg")
-        .args(["-hide_banner", "-list_devices", "true", "-f", "dshow", "-i", "dummy"])
-        .stderr(Stdio::piped())
+fn set_clipboard_text(text: &str) -> Result<(), String> {
+    // On Linux this fails when neither an X11 nor a Wayland clipboard is reachable (e.g. over ssh).
+    let mut clipboard = match arboard::Clipboard::new() {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Could not access the system clipboard: {e}")),
+    };
+
+    // X11 and Wayland clipboards are only served while their owner is alive, without a clipboard manager
+    // the text would be gone once we exit. A detached copy of this process keeps serving it instead.
+    if cfg!(target_os = "linux") {
+        drop(clipboard);
+
+        return spawn_clipboard_daemon(text);
+    }
+
+    if let Err(e) = clipboard.set_text(text) {
+        return Err(format!("Could not copy transcription to the clipboard: {e}"));
+    }
+
+    return Ok(());
+}
+
+fn spawn_clipboard_daemon(text: &str) -> Result<(), String> {
+    let current_exe = match std::env::current_exe() {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Could not copy transcription to the clipboard: {e}")),
+    };
+
+    let child = std::process::Command::new(current_exe)
+        .arg("clipboard-daemon")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
         .spawn();
 
-    let mut executed_cmd = match executed_cmd {
+    let mut child = match child {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Could not start the clipboard process: {e}")),
+    };
+
+    // Closing stdin (by dropping it) tells the daemon that the whole text has been sent.
+    if let Some(mut stdin) = child.stdin.take() {
+        use std::io::Write;
+
+        if let Err(e) = stdin.write_all(text.as_bytes()) {
+            return Err(format!("Could not copy transcription to the clipboard: {e}"));
+        }
+    }
+
+    return Ok(());
+}
+
+// Exits on its own once something else is copied. Nobody is left to see an error at this point.
+fn run_clipboard_daemon() {
+    let mut text = String::new();
+
+    if std::io::Read::read_to_string(&mut std::io::stdin(), &mut text).is_err() {
+        exit(EXIT_FAILURE);
+    }
+
+    let mut clipboard = match arboard::Clipboard::new() {
         Ok(v) => v,
-        Err(e) => return Err(format!("{e}")),
+        Err(_) => exit(EXIT_FAILURE),
     };
 
-    match executed_cmd.wait().await {
-        Err(e) => return Err(format!("{e}")),
-        _ => {},
+    #[cfg(target_os = "linux")]
+    let result = {
+        use arboard::SetExtLinux;
+
+        clipboard.set().wait().text(text)
     };
 
-    let mut stderr = match executed_cmd.stderr.take() {
-        None => return Err(format!("failed to get stderr from process")),
-        Some(v) => v,
+    #[cfg(not(target_os = "linux"))]
+    let result = clipboard.set_text(text);
+
+    if result.is_err() {
+        exit(EXIT_FAILURE);
+    }
+}
+
+// Runs through the shell, so that pipes and quoted arguments work like they do on the command line.
+// result: stdout of the command without its trailing newline, println adds one again
+async fn run_post_command(command: &str, input: String) -> Result<String, String> {
+    let (shell, shell_arg) = if cfg!(target_os = "windows") { ("cmd", "/C") } else { ("sh", "-c") };
+
+    let mut child = match tokio::process::Command::new(shell)
+        .arg(shell_arg)
+        .arg(command)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+    {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Failed to start post command \"{command}\": {e}")),
     };
-    
-    let mut output_str = String::new();
-    match stderr.read_to_string(&mut output_str).await {
-        Err(e) => return Err(format!("{e}")),
-        _ => {}
+
+    // Written from a separate task, a command that prints before reading all of its input would block otherwise.
+    let mut stdin = child.stdin.take();
+
+    let writer = tokio::spawn(async move {
+        if let Some(stdin) = stdin.as_mut() {
+            use tokio::io::AsyncWriteExt;
+
+            // A command that doesn't read its input closes the pipe early, that's up to the command.
+            let _ = stdin.write_all(input.as_bytes()).await;
+        }
+    });
+
+    let output = match child.wait_with_output().await {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Failed to run post command \"{command}\": {e}")),
+    };
+
+    let _ = writer.await;
+
+    if !output.status.success() {
+        return Err(format!(
+            "Post command \"{command}\" failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    return Ok(stdout.strip_suffix('\n').map(|v| v.strip_suffix('\r').unwrap_or(v)).unwrap_or(&stdout).to_string());
+}
+
+// One "[start - end] text" line per segment, times in seconds.
+fn format_timestamped_segments(segments: &[Segment]) -> String {
+    return segments.iter()
+        .map(|segment| format!("[{:.2} - {:.2}] {}", segment.start, segment.end, segment.text.trim()))
+        .collect::<Vec<String>>()
+        .join("\n");
+}
+
+async fn read_api_key_file(path: &str) -> Result<String, String> {
+    let contents = match tokio::fs::read_to_string(path).await {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Failed to read API key from \"{path}\": {e}")),
+    };
+
+    let api_key = contents.trim();
+
+    if api_key.is_empty() {
+        return Err(format!("API key file \"{path}\" is empty."));
+    }
+
+    return Ok(api_key.to_string());
+}
+
+// Prompt files come first, so that the inline prompt is what survives the truncation.
+async fn read_prompt(prompt_file_paths: &[String], inline_prompt: Option<String>) -> Result<Option<String>, String> {
+    let mut prompt_parts: Vec<String> = Vec::new();
+
+    for path in prompt_file_paths {
+        match tokio::fs::read_to_string(path).await {
+            Ok(v) => prompt_parts.push(v.trim().to_string()),
+            Err(e) => return Err(format!("Failed to read prompt file \"{path}\": {e}")),
+        };
+    }
+
+    prompt_parts.extend(inline_prompt.map(|v| v.trim().to_string()));
+
+    // Whisper treats an empty prompt the same as no prompt, so don't send it at all.
+    let prompt = prompt_parts.into_iter()
+        .filter(|v| !v.is_empty())
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    if prompt.is_empty() {
+        return Ok(None);
+    }
+
+    let char_count = prompt.chars().count();
+
+    if char_count > MAX_PROMPT_CHARS {
+        log::warn!("prompt is longer than the ~224 tokens Whisper considers, only its last {MAX_PROMPT_CHARS} characters are sent");
+
+        return Ok(Some(prompt.chars().skip(char_count - MAX_PROMPT_CHARS).collect()));
+    }
+
+    return Ok(Some(prompt));
+}
+
+// Only a warning, compatible servers use all kinds of key formats. Those only get the
+// whitespace checks, the format checks apply to OpenAI's own keys.
+// result: what looks wrong with the key, None when nothing does
+fn get_api_key_problem(api_key: &str, custom_base_url: bool) -> Option<&'static str> {
+    if api_key.trim().is_empty() {
+        return Some("is empty");
+    }
+
+    if api_key.trim() != api_key {
+        return Some("has leading or trailing whitespace");
+    }
+
+    if custom_base_url {
+        return None;
+    }
+
+    if !api_key.starts_with("sk-") {
+        return Some("doesn't start with \"sk-\" like OpenAI keys do");
+    }
+
+    // Even the oldest OpenAI keys are 51 characters long.
+    if api_key.len() < 40 {
+        return Some("is shorter than OpenAI keys are, it may have been cut off");
+    }
+
+    return None;
+}
+
+// In json mode the error is printed as {"error": "..."}, so that callers can always parse stderr.
+fn exit_with_code(message: impl std::fmt::Display, exit_code: i32, json: bool) -> ! {
+    if json {
+        eprintln!("{}", serde_json::json!({ "error": message.to_string() }));
+    } else {
+        eprintln!("{message}");
+    }
+
+    exit(exit_code);
+}
+
+fn exit_with_error(message: impl std::fmt::Display, json: bool) -> ! {
+    exit_with_code(message, EXIT_FAILURE, json);
+}
+
+fn exit_with_whisper_error(err: WhisperError, json: bool) -> ! {
+    let exit_code = get_exit_code(&err);
+
+    exit_with_code(err, exit_code, json);
+}
+
+fn get_exit_code(err: &WhisperError) -> i32 {
+    return match err {
+        WhisperError::RecordingKept(_, e) => get_exit_code(e),
+        WhisperError::FfmpegSpawn(_)
+        | WhisperError::FfmpegNotFound
+        | WhisperError::Ffmpeg(_)
+        | WhisperError::DeviceNotFound(_)
+        | WhisperError::NoAudioProduced(_)
+        | WhisperError::UnsupportedPlatform(_)
+        | WhisperError::InvalidProxy(_) => EXIT_SETUP,
+        WhisperError::Api(401 | 403, _, _) => EXIT_AUTH,
+        WhisperError::Api(429 | 500..=599, _, _) | WhisperError::Http(_) | WhisperError::Timeout(_) => EXIT_NETWORK,
+        WhisperError::EmptyRecording => EXIT_EMPTY,
+        WhisperError::Terminated => EXIT_TERMINATED,
+        _ => EXIT_FAILURE,
+    };
+}
+
+// SIGTERM, e.g. from a process manager, cancels the returned token. Windows has no SIGTERM, console processes
+// are stopped with Ctrl-C or Ctrl-Break there, which the library handles.
+fn listen_for_sigterm() -> tokio_util::sync::CancellationToken {
+    let terminate_token = tokio_util::sync::CancellationToken::new();
+
+    #[cfg(unix)]
+    match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+        Ok(mut signal) => {
+            let terminate_token = terminate_token.clone();
+
+            tokio::spawn(async move {
+                if signal.recv().await.is_some() {
+                    terminate_token.cancel();
+
+                    // The command is dropped right away, unless it's stuck in a blocking read like the device picker.
+                    tokio::time::sleep(std::time::Duration::from_secs(SIGTERM_GRACE_SECS)).await;
+                    exit(EXIT_TERMINATED);
+                }
+            });
+        },
+        Err(e) => log::warn!("could not handle SIGTERM: {e}"),
+    };
+
+    return terminate_token;
+}
+
+// Lists the devices on stderr, so that stdout stays clean, and reads the chosen number from stdin.
+// The list is never empty, select_audio_device exits before getting here then.
+fn pick_audio_device(audio_devices: &[String]) -> Result<String, String> {
+    for (i, device) in audio_devices.iter().enumerate() {
+        eprintln!("{}. {device}", i + 1);
+    }
+
+    eprint!("Select audio device [1-{}]: ", audio_devices.len());
+
+    let mut line = String::new();
+
+    if let Err(e) = std::io::stdin().read_line(&mut line) {
+        return Err(format!("Failed to read the selected audio device: {e}"));
+    }
+
+    let line = line.trim();
+
+    if line.parse::<usize>().is_err() {
+        return Err(format!("Expected a device number, received \"{line}\"."));
+    }
+
+    return resolve_audio_device(audio_devices, line);
+}
+
+// Where and how the results of transcribe-dir end up, set by the same flags as for a single file.
+struct DirOutput {
+    json: bool,
+    output_file: Option<String>,
+    copy_to_clipboard: bool,
+    fail_on_empty: bool,
+    quiet: bool,
+}
+
+// Prints every result as soon as it's done, prefixed with its file name. Failed files are reported
+// on stderr and don't stop the others.
+// result: true when every file was transcribed (to something non-empty with `fail_on_empty`)
+async fn run_transcribe_dir(
+    openai_auth_key: String,
+    file_paths: Vec<String>,
+    options: &TranscriptionOptions,
+    concurrency: usize,
+    output: DirOutput,
+) -> bool {
+    let DirOutput { json, output_file, copy_to_clipboard, fail_on_empty, quiet } = output;
+
+    let results = match whisper_connector::transcribe_files(openai_auth_key, file_paths, options, concurrency) {
+        Ok(v) => v,
+        Err(e) => exit_with_whisper_error(e, json),
     };
 
-    let output_lines = output_str.lines().into_iter().map(|ele| ele.to_string()).collect::<Vec<String>>();
+    let mut results = std::pin::pin!(results);
+    let mut outputs: Vec<String> = Vec::new();
+    let mut all_transcribed = true;
 
-    let mut devices: Vec<String> = Vec::new();
+    while let Some((file_path, result)) = results.next().await {
+        let transcription = match result {
+            Ok(v) => v,
+            Err(e) => {
+                if json {
+                    eprintln!("{}", serde_json::json!({ "file": file_path, "error": e.to_string() }));
+                } else {
+                    eprintln!("{file_path}: {e}");
+                }
 
-    for output_line in output_lines {
-        if !output_line.contains("dshow @") || 
-        output_line.contains("]  Alternative name \"") ||
-        !output_line.contains(" (audio)")
-        { continue; }
+                all_transcribed = false;
+                continue;
+            },
+        };
 
-        let start_idx = output_line.find(" \"");
-        let end_idx = output_line.find("\" ");
+        if fail_on_empty && transcription.text.trim().is_empty() {
+            if json {
+                eprintln!("{}", serde_json::json!({ "file": file_path, "error": EMPTY_TRANSCRIPTION_MESSAGE }));
+            } else {
+                eprintln!("{file_path}: {EMPTY_TRANSCRIPTION_MESSAGE}");
+            }
 
-        if start_idx.is_none() || end_idx.is_none() {
-            continue;
+            all_transcribed = false;
         }
 
-        let start_idx = start_idx.unwrap() + 2;
-        let end_idx = end_idx.unwrap();
+        if !quiet {
+            print_dropped_segments(transcription.dropped_segments, Some(&file_path));
+        }
+
+        // verbose_json is also requested just for the detected language, segments only count with --timestamps.
+        let timestamped = transcription.verbose.as_ref().filter(|_| options.timestamps);
+
+        let output = if json {
+            let json_output = JsonTranscriptionOutput {
+                language: options.language.clone(),
+                detected_language: transcription.verbose.as_ref().and_then(|v| v.language.clone()),
+                device: None,
+                segments: timestamped.map(|v| v.segments.clone()),
+                words: timestamped.map(|v| v.words.clone()),
+                recording: None,
+                file: Some(file_path),
+                text: transcription.text,
+            };
+
+            match serde_json::to_string(&json_output) {
+                Ok(v) => v,
+                Err(e) => exit_with_error(e, json),
+            }
+        } else {
+            let file_name = std::path::Path::new(&file_path)
+                .file_name()
+                .map(|v| v.to_string_lossy().to_string())
+                .unwrap_or(file_path.clone());
+
+            match timestamped {
+                Some(verbose) => format!("{file_name}:\n{}", format_timestamped_segments(&verbose.segments)),
+                None => format!("{file_name}: {}", transcription.text),
+            }
+        };
+
+        if output_file.is_none() {
+            println!("{output}");
+        }
+
+        outputs.push(output);
+    }
+
+    if copy_to_clipboard {
+        if let Err(e) = set_clipboard_text(&outputs.join("\n")) {
+            exit_with_error(e, json);
+        }
+    }
 
-        if start_idx > end_idx {
-            return Err(format!("malformed line returned from ffmpeg, parsing error: \"{}\"", output_line));
+    if let Some(path) = output_file {
+        if let Err(e) = tokio::fs::write(&path, outputs.join("\n")).await {
+            exit_with_error(format!("Failed to write transcriptions to \"{path}\": {e}"), json);
         }
+    }
+
+    return all_transcribed;
+}
+
+fn get_no_devices_message() -> String {
+    let message = "No audio input devices found. Check microphone permissions and drivers.";
 
-        let device_name = &output_line[start_idx..end_idx];
+    // Without the permission avfoundation lists no devices at all instead of failing.
+    if cfg!(target_os = "macos") {
+        return format!("{message} On macOS the terminal needs microphone access, see System Settings > Privacy & Security > Microphone.");
+    }
 
-        devices.push(device_name.to_string());
+    // dshow lists nothing as well when desktop apps are denied the microphone.
+    if cfg!(target_os = "windows") {
+        return format!("{message} On Windows check that Settings > Privacy & security > Microphone allows desktop apps to access the microphone.");
     }
 
-    return Ok(devices);
+    return message.to_string();
 }
 
-async fn send_request(
-    language: String, 
-    openai_auth_key: String, 
-    file_name: String, 
-    file: tokio::fs::File
-) -> Result<String, String> {
-    let client = reqwest::Client::new();
+fn print_check(passed: bool, description: &str) {
+    let status = if passed { " OK " } else { "FAIL" };
 
-    let stream = FramedRead::new(file, BytesCodec::new());
-    let file_body = reqwest::Body::wrap_stream(stream);
+    println!("[{status}] {description}");
+}
 
-    let file_part = reqwest::multipart::Part::stream(file_body)
-        .file_name(file_name)
-        .mime_str("audio/mpeg")
-        .map_err(error_to_string)?;
+// Azure OpenAI endpoint and deployment with --azure.
+fn get_azure(connection: &ConnectionArgs) -> Result<Option<(String, AzureDeployment)>, String> {
+    if !connection.azure {
+        return Ok(None);
+    }
 
-    let form = reqwest::multipart::Form::new()
-        .text("model", "whisper-1")
-        .text("language", language)
-        .part("file", file_part);
+    return get_azure_deployment().map(Some);
+}
 
-    let response = client.post("https://api.openai.com/v1/audio/transcriptions")
-        .bearer_auth(openai_auth_key)
-        .multipart(form)
-        .send()
-        .await
-        .expect("Should return response from server");
+// The file wins over the environment variable, it doesn't show up in process listings.
+// Azure keys usually live in their own variable, OPENAI_AUTH_KEY still works for them.
+// result: None when the key has been set nowhere
+async fn get_api_key(connection: &ConnectionArgs) -> Result<Option<String>, String> {
+    if let Some(path) = &connection.api_key_file {
+        return read_api_key_file(path).await.map(Some);
+    }
 
-    let response = response.error_for_status().map_err(error_to_string)?;
+    let azure_api_key = if connection.azure { std::env::var("AZURE_OPENAI_API_KEY").ok() } else { None };
 
-    let text = response.text().await.map_err(error_to_string)?;
+    return Ok(azure_api_key.or(std::env::var("OPENAI_AUTH_KEY").ok()));
+}
 
-    let obj: SimpleOpenAIResponse = serde_json::from_str(&text).map_err(error_to_string)?;
+// result: where `get_api_key` looks for the key, for messages
+fn get_api_key_source(connection: &ConnectionArgs) -> &'static str {
+    return match (&connection.api_key_file, connection.azure) {
+        (Some(_), _) => "--api-key-file",
+        (None, true) => "AZURE_OPENAI_API_KEY or OPENAI_AUTH_KEY",
+        (None, false) => "OPENAI_AUTH_KEY",
+    };
+}
 
-    return Ok(obj.text);
+fn get_base_url(azure: Option<&(String, AzureDeployment)>, config: &config::Config) -> String {
+    return match azure {
+        Some((endpoint, _)) => endpoint.clone(),
+        None => std::env::var("OPENAI_BASE_URL").ok()
+            .or(config.base_url.clone())
+            .unwrap_or(DEFAULT_OPENAI_BASE_URL.to_string()),
+    };
 }
 
-// result:
-// .0 - filename
-// .1 - absolute path
-fn get_audio_sample_absolute_file_path() -> Result<(String, String), String> {
-    let temp_dir = std::env::temp_dir();
+fn get_proxy(connection: &ConnectionArgs) -> Option<String> {
+    return connection.proxy.clone().filter(|proxy| !proxy.trim().is_empty());
+}
 
-    let file_seed: String = rand::thread_rng()
-        .sample_iter(rand::distributions::Alphanumeric)
-        .take(8)
-        .map(char::from)
-        .collect();
+// Uses the same key, endpoint, proxy and TLS settings as transcribing, so that a passed check means they work there too.
+// result: true when every critical check passed
+async fn run_doctor(connection: &ConnectionArgs) -> bool {
+    let mut all_passed = true;
 
-    let file_name = format!("whisper_connector_audio_sample_{file_seed}.mp3");
+    let ffmpeg_found = match whisper_connector::get_ffmpeg_version().await {
+        Ok(v) => {
+            print_check(true, &format!("ffmpeg {v} found"));
+            true
+        },
+        Err(e) => {
+            print_check(false, &format!("ffmpeg: {e}"));
+            false
+        },
+    };
+
+    all_passed &= ffmpeg_found;
 
-    let absolute_file_path = temp_dir.join(&file_name);
+    if ffmpeg_found {
+        match whisper_connector::get_available_capture_backends().await {
+            Ok(v) if !v.is_empty() => print_check(true, &format!("capture backend available: {}", v.join(", "))),
+            Ok(_) => {
+                let expected = whisper_connector::get_capture_backends().join(" or ");
+                print_check(false, &format!("ffmpeg was built without a capture backend for {} (expected {expected})", std::env::consts::OS));
+                all_passed = false;
+            },
+            Err(e) => {
+                print_check(false, &format!("could not list ffmpeg capture backends: {e}"));
+                all_passed = false;
+            },
+        };
 
-    match absolute_file_path.to_str() {
-        Some(v) => return Ok((file_name, v.to_string())),
-        None => return Err("Could not get temporary audio sample file path.".to_string()),
+        match whisper_connector::list_devices().await {
+            Ok(v) if !v.is_empty() => print_check(true, &format!("{} audio device(s) found", v.len())),
+            Ok(_) => {
+                print_check(false, &get_no_devices_message());
+                all_passed = false;
+            },
+            Err(e) => {
+                print_check(false, &format!("could not list audio devices: {e}"));
+                all_passed = false;
+            },
+        };
     }
-}
 
-async fn execute_parse_command(openai_auth_key: String, language: String, audio_device: String) -> Result<String, String> {
-    let cancellation_token = tokio_util::sync::CancellationToken::new();
-    let ctrlc_cancellation_token = cancellation_token.clone();
-    let _ = ctrlc::set_handler(move || ctrlc_cancellation_token.cancel());
+    let config = match config::load_config() {
+        Ok(v) => v,
+        Err(e) => {
+            print_check(false, &e);
+            all_passed = false;
+            config::Config::default()
+        },
+    };
 
-    let (file_name, audio_sample_file_path) = match get_audio_sample_absolute_file_path() {
+    let azure = match get_azure(connection) {
         Ok(v) => v,
         Err(e) => {
-            println!("Error occured: {e}");
-            exit(1);
+            print_check(false, &e);
+            all_passed = false;
+            None
         },
     };
 
-    let _ = std::fs::remove_file(&audio_sample_file_path);
-
-    let cmd = tokio::process::Command::new("ffmpeg")
-        .args(&[
-            // "-list_devices", "true",
-            // "-loglevel", "quiet", 
-            "-y",
-            "-f", "dshow",
-            "-i", format!("audio={audio_device}").as_str(),
-            &audio_sample_file_path,
-        ])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .stdin(Stdio::piped())
-        .spawn();
+    let api_key_source = get_api_key_source(connection);
 
-    if let Err(e) = &cmd {
-        cancellation_token.cancel();
-        return Err(format!("could not spawn ffmpeg process that listens to microphone: {}", e));
+    match get_api_key(connection).await {
+        Ok(Some(v)) if !v.trim().is_empty() => print_check(true, &format!("API key found in {api_key_source}")),
+        Ok(_) => {
+            print_check(false, &format!("no API key in {api_key_source}"));
+            all_passed = false;
+        },
+        Err(e) => {
+            print_check(false, &e);
+            all_passed = false;
+        },
+    };
+
+    let base_url = get_base_url(azure.as_ref(), &config);
+
+    match whisper_connector::check_api_reachable(&base_url, 10, get_proxy(connection).as_deref(), connection.insecure).await {
+        Ok(status) => print_check(true, &format!("{base_url} is reachable (HTTP {})", status.as_u16())),
+        Err(e) => {
+            print_check(false, &format!("{base_url} is not reachable: {e}"));
+            all_passed = false;
+        },
+    };
+
+    return all_passed;
+}
+
+fn is_language_arg(arg: &str) -> bool {
+    return arg == AUTO_LANGUAGE || whisper_connector::is_supported_language(arg);
+}
+
+// Still accepted, but --language is the way to go, the argument goes away in the next release.
+fn get_positional_language(language: Option<String>) -> Option<String> {
+    // An invalid one gets its own error, more likely about swapped arguments.
+    if let Some(language) = language.as_ref().filter(|v| is_language_arg(v)) {
+        log::warn!("passing the language as an argument is deprecated, use --language {language} instead");
     }
 
-    let mut cmd = cmd.unwrap();
+    return language;
+}
 
-    let mut stdin = tokio::io::stdin();
+// result: value of --env-file, looked up in the raw arguments like --json
+fn get_env_file_arg(cmd_args: &[std::ffi::OsString]) -> Option<std::path::PathBuf> {
+    let idx = cmd_args.iter().position(|arg| arg == "--env-file" || arg.to_str().is_some_and(|v| v.starts_with("--env-file=")))?;
 
-    tokio::select! {
-        _ = cancellation_token.cancelled() => {
-            if let Err(e) = cmd.kill().await {
-                return Err(format!("Failed to kill ffmpeg instance: {}", e));
-            }
+    return match cmd_args[idx].to_str().and_then(|v| v.strip_prefix("--env-file=")) {
+        Some(v) => Some(v.into()),
+        None => cmd_args.get(idx + 1).map(|v| v.into()),
+    };
+}
 
-            return Ok(String::new());
+// result: whether the subcommand got --json, an option value that happens to be "--json" doesn't count
+fn get_json_flag(matches: &clap::ArgMatches) -> bool {
+    return match matches.subcommand() {
+        Some((_, subcommand_matches)) => matches!(subcommand_matches.try_get_one::<bool>("json"), Ok(Some(true))),
+        None => false,
+    };
+}
+
+// Variables that are already set win over the file. A missing .env in the current directory is fine,
+// a missing --env-file is not.
+fn load_env_file(env_file: Option<std::path::PathBuf>) -> Result<(), String> {
+    let result = match &env_file {
+        Some(path) => dotenvy::from_path(path).map(|_| ()),
+        None => match dotenvy::dotenv() {
+            Err(e) if e.not_found() => Ok(()),
+            v => v.map(|_| ()),
         },
-        _ = stdin.read_u8() => {},
     };
 
-    let mut cmd_stdin = match cmd.stdin.take() {
-        Some(v) => v,
-        None => return Err(format!("Unknown error occured while taking STDIN from ffmpeg process."))
+    if let Err(e) = result {
+        let path = match &env_file {
+            Some(v) => v.display().to_string(),
+            None => ".env".to_string(),
+        };
+
+        return Err(format!("Failed to load \"{path}\": {e}"));
+    }
+
+    return Ok(());
+}
+
+// Azure OpenAI is configured through the environment only, like the API key.
+// result: the resource endpoint and the deployment on it
+fn get_azure_deployment() -> Result<(String, AzureDeployment), String> {
+    let endpoint = match std::env::var("AZURE_OPENAI_ENDPOINT") {
+        Ok(v) if !v.trim().is_empty() => v,
+        _ => return Err("--azure requires the AZURE_OPENAI_ENDPOINT environment variable, e.g. https://my-resource.openai.azure.com.".to_string()),
     };
 
-    if let Err(e) = cmd_stdin.write(b"q").await {
-        return Err(format!("Failed to send 'q' key to ffmpeg instance: {}", e));
+    let deployment = match std::env::var("AZURE_DEPLOYMENT") {
+        Ok(v) if !v.trim().is_empty() => v,
+        _ => return Err("--azure requires the AZURE_DEPLOYMENT environment variable, the name of the Whisper deployment.".to_string()),
+    };
+
+    let api_version = std::env::var("AZURE_API_VERSION").ok()
+        .filter(|v| !v.trim().is_empty())
+        .unwrap_or(DEFAULT_AZURE_API_VERSION.to_string());
+
+    return Ok((endpoint.trim().to_string(), AzureDeployment { deployment: deployment.trim().to_string(), api_version }));
+}
+
+// Points at the likely mistake behind an unknown language, swapped arguments above all.
+// result: sentence appended to the error message, empty when there is nothing to suggest
+fn get_language_hint(language: &str, source: &AudioSource) -> String {
+    let (other_arg, usage) = match source {
+        AudioSource::Device { device_arg, .. } => (device_arg.as_deref(), "transcribe --language <language> <device>"),
+        AudioSource::File { path, .. } => (Some(path.as_str()), "transcribe-file <path> --language <language>"),
+        AudioSource::Dir { dir, .. } => (Some(dir.as_str()), "transcribe-dir <dir> --language <language>"),
+    };
+
+    if let Some(other_arg) = other_arg.filter(|v| is_language_arg(v)) {
+        return format!(" \"{other_arg}\" is a language, the arguments seem to be swapped: {usage}.");
     }
 
-    if let Err(e) = cmd_stdin.flush().await {
-        return Err(format!("Failed to flush stdin of ffmpeg instance: {}", e));
+    if let Some(code) = whisper_connector::get_language_code(language) {
+        return format!(" Use its code '{code}' instead.");
     }
 
-    if let Err(e) = cmd.wait().await {
-        return Err(format!("Failed to kill ffmpeg instance: {}", e));
+    // Device numbers and names like "hw:CARD=PCH,DEV=0" or "Microphone (Realtek)" never look like a code.
+    let looks_like_device = language.parse::<usize>().is_ok() || language.contains([':', ',', '(', ' ']);
+
+    if looks_like_device && matches!(source, AudioSource::Device { .. }) {
+        return format!(" It looks like a device, pass the language with --language: {usage}.");
     }
 
-    let file = match tokio::fs::File::open(audio_sample_file_path).await {
+    return String::new();
+}
+
+// Lists the devices, which takes ffmpeg a moment, to check the device or to pick one.
+async fn select_audio_device(device_arg: Option<String>, recording: &RecordingArgs, quiet: bool, json: bool) -> String {
+    let audio_devices = if recording.loopback {
+        whisper_connector::list_loopback_devices().await
+    } else {
+        whisper_connector::list_devices().await
+    };
+
+    let audio_devices = match audio_devices {
+        Ok(v) if v.is_empty() && recording.loopback => exit_with_code(LOOPBACK_HINT, EXIT_SETUP, json),
+        Ok(v) if v.is_empty() => exit_with_code(get_no_devices_message(), EXIT_SETUP, json),
+        Ok(v) => v,
+        Err(e) => exit_with_whisper_error(e, json),
+    };
+
+    // A saved device that is no longer connected is ignored, the picker asks for another one then.
+    let last_device = state::load_last_device().filter(|device| audio_devices.contains(device));
+
+    let audio_device = match (recording.device_alt.as_deref(), device_arg, last_device) {
+        (Some(alternative_name), _, _) => find_device_by_alternative_name(alternative_name).await,
+        (None, Some(device_arg), _) => resolve_audio_device(&audio_devices, &device_arg),
+        (None, None, Some(last_device)) => Ok(last_device),
+        // Usually there is just one, the monitor of the default output.
+        (None, None, None) if recording.loopback => Ok(audio_devices[0].clone()),
+        (None, None, None) if std::io::stdin().is_terminal() && !quiet => pick_audio_device(&audio_devices),
+        (None, None, None) => Err("Missing audio device, pass it as an argument or set `default_device` in the config file.".to_string()),
+    };
+
+    // Unknown devices, numbers out of range and a missing device are all audio device problems.
+    return match audio_device {
         Ok(v) => v,
-        Err(e) => return Err(format!("Error occured while trying to read recorded audio sample: {e}"))
+        Err(e) => exit_with_code(e, EXIT_SETUP, json),
     };
+}
+
+// result: name of the device, the recording itself is opened by the alternative name
+async fn find_device_by_alternative_name(alternative_name: &str) -> Result<String, String> {
+    if !cfg!(target_os = "windows") {
+        return Err("--device-alt is only supported on Windows.".to_string());
+    }
 
-    let response = match send_request(language, openai_auth_key, file_name, file).await {
+    let devices = match whisper_connector::list_device_alternative_names().await {
         Ok(v) => v,
-        Err(e) => return Err(format!("{e}")),
+        Err(e) => return Err(e.to_string()),
     };
 
-    return Ok(response);
+    return match devices.into_iter().find(|(_, v)| v.as_deref() == Some(alternative_name)) {
+        Some((device, _)) => Ok(device),
+        None => Err(format!("No audio device has the alternative name \"{alternative_name}\", see `devices --alternative-names`.")),
+    };
 }
 
-#[tokio::main]
-async fn main() {
-    let cmd_args = gd_terminal_utils::get_cmd_args();
+// Accepts either the exact device name or its number as printed by the `devices` command.
+fn resolve_audio_device(audio_devices: &[String], device_arg: &str) -> Result<String, String> {
+    if let Ok(device_number) = device_arg.parse::<usize>() {
+        if device_number < 1 || device_number > audio_devices.len() {
+            return Err(format!(
+                "Audio device number {device_number} is out of range, valid range: 1-{}.",
+                audio_devices.len()
+            ));
+        }
 
-    if cmd_args.len() < 1 {
-        eprintln!("Expected at least 1 argument, received {}.", cmd_args.len());
-        exit(1);
+        return Ok(audio_devices[device_number - 1].clone());
     }
 
-    match cmd_args[0].as_str() {
-        "devices" => {
-            let audio_devices = match get_audio_devices().await {
+    if !audio_devices.iter().any(|device| device == device_arg) {
+        // A lone language name ends up here, only codes are taken as the language.
+        if let Some(code) = whisper_connector::get_language_code(device_arg) {
+            return Err(format!("This audio device does not exist. For the language pass its code '{code}' instead of \"{device_arg}\"."));
+        }
+
+        return Err("This audio device does not exist.".to_string());
+    }
+
+    return Ok(device_arg.to_string());
+}
+
+// Not hidden behind --verbose, a transcription missing parts for no visible reason would be confusing.
+fn print_dropped_segments(dropped_segments: usize, file_path: Option<&str>) {
+    if dropped_segments == 0 {
+        return;
+    }
+
+    match file_path {
+        Some(path) => eprintln!("{path}: dropped {dropped_segments} low-confidence segment(s)"),
+        None => eprintln!("Dropped {dropped_segments} low-confidence segment(s)"),
+    }
+}
+
+// result: what is printed or written to the output file, the recording path for dry runs
+fn format_transcription(
+    transcription: Transcription,
+    options: &TranscriptionOptions,
+    output_format: OutputFormat,
+    json: bool,
+    audio_device: Option<String>,
+    file_path: Option<String>,
+) -> String {
+    // verbose_json is also requested just for the detected language, segments only count with --timestamps.
+    let timestamped = transcription.verbose.as_ref().filter(|_| options.timestamps);
+
+    if json {
+        let json_output = JsonTranscriptionOutput {
+            text: transcription.text,
+            language: options.language.clone(),
+            detected_language: transcription.verbose.as_ref().and_then(|v| v.language.clone()),
+            file: file_path,
+            device: audio_device,
+            segments: timestamped.map(|v| v.segments.clone()),
+            words: timestamped.map(|v| v.words.clone()),
+            recording: transcription.recording_path.clone(),
+        };
+
+        return match serde_json::to_string(&json_output) {
+            Ok(v) => v,
+            Err(e) => exit_with_error(e, json),
+        };
+    }
+
+    if let Some(recording_path) = transcription.recording_path {
+        return recording_path;
+    }
+
+    return match (timestamped, output_format) {
+        (Some(verbose), OutputFormat::Srt) => whisper_connector::segments_to_srt(&verbose.segments),
+        (Some(verbose), OutputFormat::Vtt) => whisper_connector::segments_to_vtt(&verbose.segments),
+        (Some(verbose), OutputFormat::Text) => format_timestamped_segments(&verbose.segments),
+        (None, _) => transcription.text,
+    };
+}
+
+// Where the audio of the transcribe* commands comes from.
+enum AudioSource {
+    Device { device_arg: Option<String>, recording: RecordingArgs },
+    // "-" is stdin, see `whisper_connector::transcribe_stdin`.
+    File { path: String, format: Option<AudioFormat>, transcode: bool },
+    Dir { dir: String, concurrency: usize, rpm: Option<u32> },
+}
+
+fn get_audio_format(format_arg: Option<AudioFormat>, config: &config::Config) -> Result<AudioFormat, String> {
+    return match (format_arg, &config.format) {
+        (Some(v), _) => Ok(v),
+        (None, None) => Ok(AudioFormat::Mp3),
+        (None, Some(v)) => match AudioFormat::from_name(v) {
+            Some(v) => Ok(v),
+            None => Err(format!("Unknown format \"{v}\" in the config file, supported formats: 'mp3', 'wav', 'm4a', 'flac'.")),
+        },
+    };
+}
+
+fn get_after_help() -> String {
+    let precedence = "Command line arguments override environment variables (exported ones over those from .env), which override the config file.";
+
+    let exit_codes = format!(
+        "Exit codes:\n  {EXIT_FAILURE}  any other error\n  {EXIT_SETUP}  ffmpeg, audio device, config file or proxy problem\n  {EXIT_AUTH}  missing or rejected API key\n  {EXIT_NETWORK}  connection problem, timeout, rate limit or server error\n  {EXIT_EMPTY}  empty recording, or empty transcription with --fail-on-empty\n  {EXIT_TERMINATED}  terminated by SIGTERM, a running recording is discarded"
+    );
+
+    return match config::get_config_path() {
+        Some(config_path) => format!(
+            "Defaults for language, default_device, format, model, base_url and cost_per_minute can be set in {}.\n{precedence}\n\n{exit_codes}",
+            config_path.display()
+        ),
+        None => format!("{precedence}\n\n{exit_codes}"),
+    };
+}
+
+async fn run_transcription(source: AudioSource, language_arg: Option<String>, translate: bool, args: TranscriptionArgs, verbose: bool, quiet: bool) {
+    let json = args.json;
+
+    let config = match config::load_config() {
+        Ok(v) => v,
+        Err(e) => exit_with_code(e, EXIT_SETUP, json),
+    };
+
+    if let AudioSource::Device { recording, .. } = &source {
+        if recording.hold && !std::io::stdin().is_terminal() {
+            exit_with_error("--hold requires an interactive terminal.", json);
+        }
+
+        // Chunk timestamps start at 0 each time, they can't be combined into one timeline.
+        if (recording.chunked || recording.chunk_secs.is_some()) && args.timestamps {
+            exit_with_error("--chunked can't be combined with --timestamps.", json);
+        }
+
+        // Chunks are sent as plain transcriptions, without the probabilities to filter by.
+        if (recording.chunked || recording.chunk_secs.is_some()) && (args.min_logprob.is_some() || args.max_no_speech.is_some()) {
+            exit_with_error("--chunked can't be combined with --min-logprob or --max-no-speech.", json);
+        }
+
+        // Chunks are separate files, there is no single recording that could be kept.
+        if (recording.chunked || recording.chunk_secs.is_some()) && recording.keep_recording.is_some() {
+            exit_with_error("--chunked can't be combined with --keep-recording.", json);
+        }
+
+        // Only chunks are saved as they complete, a single recording has nothing to resume from.
+        if recording.resume.is_some() && !recording.chunked && recording.chunk_secs.is_none() {
+            exit_with_error("--resume requires --chunked.", json);
+        }
+
+        if (recording.chunked || recording.chunk_secs.is_some()) && recording.keep_on_error {
+            exit_with_error("--chunked can't be combined with --keep-on-error.", json);
+        }
+
+        // Chunks are printed as soon as they arrive, before the whole text could be piped anywhere.
+        if (recording.chunked || recording.chunk_secs.is_some()) && args.post_command.is_some() {
+            exit_with_error("--chunked can't be combined with --post-command.", json);
+        }
+
+        if let (Some(path), false) = (&recording.keep_recording, recording.overwrite) {
+            if std::path::Path::new(path).exists() {
+                exit_with_error(format!("\"{path}\" already exists, pass --overwrite to replace it."), json);
+            }
+        }
+    }
+
+    // Subtitles are built from segments, which are only returned in verbose_json.
+    if args.output_format != OutputFormat::Text && !args.timestamps {
+        exit_with_error("Subtitle output requires segment timestamps, add the --timestamps flag.", json);
+    }
+
+    // Timestamps only come with verbose_json.
+    if args.response_format == ResponseFormat::Text && args.timestamps {
+        exit_with_error("--response-format text can't be combined with --timestamps.", json);
+    }
+
+    // The probabilities only come with verbose_json.
+    if args.response_format == ResponseFormat::Text && (args.min_logprob.is_some() || args.max_no_speech.is_some()) {
+        exit_with_error("--response-format text can't be combined with --min-logprob or --max-no-speech.", json);
+    }
+
+    if !args.allowed_languages.is_empty() {
+        // Chunks are sent as plain transcriptions, without a detected language to check.
+        if matches!(&source, AudioSource::Device { recording, .. } if recording.chunked || recording.chunk_secs.is_some()) {
+            exit_with_error("--allowed-languages can't be combined with --chunked.", json);
+        }
+
+        if translate {
+            exit_with_error("--allowed-languages can't be combined with translate.", json);
+        }
+
+        if args.response_format == ResponseFormat::Text {
+            exit_with_error("--allowed-languages can't be combined with --response-format text.", json);
+        }
+    }
+
+    // Several subtitle files can't be written into one output.
+    if matches!(source, AudioSource::Dir { .. }) && args.output_format != OutputFormat::Text {
+        exit_with_error("transcribe-dir only supports the text output format.", json);
+    }
+
+    if matches!(source, AudioSource::Dir { .. }) && args.post_command.is_some() {
+        exit_with_error("transcribe-dir can't be combined with --post-command.", json);
+    }
+
+    let dry_run = matches!(&source, AudioSource::Device { recording, .. } if recording.dry_run);
+
+    let azure = match get_azure(&args.connection) {
+        Ok(v) => v,
+        Err(e) => exit_with_code(e, EXIT_SETUP, json),
+    };
+
+    let openai_auth_key = match get_api_key(&args.connection).await {
+        Ok(Some(v)) => v,
+        Ok(None) if dry_run => String::new(),
+        Ok(None) if azure.is_some() => exit_with_code("Required AZURE_OPENAI_API_KEY environment variable (or --api-key-file) has not been set.", EXIT_AUTH, json),
+        Ok(None) => exit_with_code("Required OPENAI_AUTH_KEY environment variable (or --api-key-file) has not been set.", EXIT_AUTH, json),
+        Err(e) => exit_with_error(e, json),
+    };
+
+    let prompt = match read_prompt(&args.prompt_file, args.prompt).await {
+        Ok(v) => v,
+        Err(e) => exit_with_error(e, json),
+    };
+
+    let model = args.model.or(config.model.clone()).unwrap_or(DEFAULT_MODEL.to_string());
+
+    // Servers differ in the models they support, so anything non-empty is passed through.
+    if model.trim().is_empty() {
+        exit_with_error("Model must not be empty.", json);
+    }
+
+    // Translation always outputs English and Whisper detects the spoken language itself,
+    // so `translate` takes no language argument and skips language validation.
+    let language = if translate {
+        None
+    } else {
+        // Whisper detects the language on its own when none is given anywhere.
+        let language = language_arg.or(config.language.clone()).unwrap_or(AUTO_LANGUAGE.to_string());
+
+        if !is_language_arg(&language) {
+            exit_with_error(format!(
+                "Unknown language \"{language}\", expected an ISO-639-1 code supported by Whisper (e.g. 'en', 'pl') or '{AUTO_LANGUAGE}'.{}",
+                get_language_hint(&language, &source)
+            ), json);
+        }
+
+        if language == AUTO_LANGUAGE {
+            None
+        } else {
+            Some(language)
+        }
+    };
+
+    let base_url = get_base_url(azure.as_ref(), &config);
+
+    // A likely 401 is worth a warning before spending an upload on it.
+    if !dry_run {
+        if let Some(problem) = get_api_key_problem(&openai_auth_key, base_url.trim_end_matches('/') != DEFAULT_OPENAI_BASE_URL) {
+            log::warn!("the API key {problem}");
+        }
+    }
+
+    // Only shown with --json (and logged with --verbose), not worth the bigger response otherwise.
+    // --allowed-languages can't do without it.
+    let detect_language = (json || verbose || !args.allowed_languages.is_empty())
+        && language.is_none()
+        && args.response_format == ResponseFormat::Json;
+
+    let options = TranscriptionOptions {
+        base_url: base_url.trim_end_matches('/').to_string(),
+        azure: azure.map(|(_, deployment)| deployment),
+        model,
+        language,
+        prompt,
+        temperature: args.temperature,
+        translate,
+        timeout_secs: args.timeout,
+        timestamps: args.timestamps,
+        detect_language,
+        allowed_languages: args.allowed_languages,
+        print_raw_response: args.raw,
+        buffered_upload: args.buffered_upload,
+        rate_limiter: match &source {
+            AudioSource::Dir { rpm: Some(rpm), .. } => Some(std::sync::Arc::new(RateLimiter::new(*rpm))),
+            _ => None,
+        },
+        min_avg_logprob: args.min_logprob,
+        max_no_speech_prob: args.max_no_speech,
+        proxy: get_proxy(&args.connection),
+        response_format: args.response_format,
+        insecure: args.connection.insecure,
+        mime_type: args.mime.filter(|v| !v.trim().is_empty()),
+        transcode: matches!(source, AudioSource::File { transcode: true, .. }),
+        organization: args.organization.filter(|v| !v.trim().is_empty()),
+        project: args.project.filter(|v| !v.trim().is_empty()),
+        // Parallel uploads (directories, chunks) would draw over each other, JSON callers parse stderr.
+        show_upload_progress: !quiet && !json && std::io::stderr().is_terminal() && match &source {
+            AudioSource::Device { recording, .. } => !recording.chunked && recording.chunk_secs.is_none(),
+            AudioSource::File { .. } => true,
+            AudioSource::Dir { .. } => false,
+        },
+    };
+
+    // The API key and the audio could be intercepted, not the kind of thing to hide behind --verbose.
+    if options.insecure {
+        eprintln!("WARNING: TLS certificate verification is disabled (--insecure), the connection to {} is not secure.", options.base_url);
+    }
+
+    let output_file = args.output_file;
+
+    let (transcription, audio_device, file_path, already_printed) = match source {
+        AudioSource::Dir { dir, concurrency, .. } => {
+            let file_paths = match whisper_connector::find_audio_files(&dir) {
+                Ok(v) if v.is_empty() => exit_with_error(format!("No audio files found in \"{dir}\"."), json),
                 Ok(v) => v,
-                Err(e) => {
-                    eprintln!("{e}");
-                    exit(1);
-                }
+                Err(e) => exit_with_whisper_error(e, json),
+            };
+
+            let output = DirOutput {
+                json,
+                output_file,
+                copy_to_clipboard: args.clipboard,
+                fail_on_empty: args.fail_on_empty,
+                quiet,
             };
 
-            let mut i = 1;
+            let all_transcribed = run_transcribe_dir(openai_auth_key, file_paths, &options, concurrency, output).await;
+
+            if !all_transcribed {
+                exit(EXIT_FAILURE);
+            }
+
+            return;
+        },
+        AudioSource::File { path, format, .. } => {
+            let transcription = if path == "-" {
+                let format = match get_audio_format(format, &config) {
+                    Ok(v) => v,
+                    Err(e) => exit_with_error(e, json),
+                };
+
+                whisper_connector::transcribe_stdin(openai_auth_key, format, &options).await
+            } else {
+                whisper_connector::transcribe_file(openai_auth_key, path.clone(), &options).await
+            };
 
-            for device in audio_devices {
-                println!("{i}. {device}");
-                i += 1;
+            match transcription {
+                Ok(v) => (v, None, Some(path), false),
+                Err(e) => exit_with_whisper_error(e, json),
             }
         },
-        "transcribe" => {
-            let openai_auth_key = match std::env::var("OPENAI_AUTH_KEY") {
+        AudioSource::Device { device_arg, recording } => {
+            if let Err(e) = whisper_connector::check_ffmpeg().await {
+                exit_with_whisper_error(e, json);
+            }
+
+            let device_arg = device_arg.or(config.default_device.clone());
+            let loopback = recording.loopback;
+
+            // A name is passed to ffmpeg as it is and a wrong one fails once recording starts.
+            // Device numbers, --device-alt, the loopback default and the picker still need the list.
+            let unvalidated_device = match &device_arg {
+                _ if !recording.no_validate_device || recording.device_alt.is_some() => None,
+                Some(device_arg) if device_arg.parse::<usize>().is_err() => Some(device_arg.clone()),
+                Some(_) => None,
+                // The saved device is a microphone, loopback recordings always look up theirs.
+                None if loopback => None,
+                None => state::load_last_device(),
+            };
+
+            let audio_device = match unvalidated_device {
+                Some(v) => v,
+                None => select_audio_device(device_arg, &recording, quiet, json).await,
+            };
+
+            let keep_recording_extension = recording.keep_recording.as_ref()
+                .and_then(|path| std::path::Path::new(path).extension())
+                .map(|v| v.to_string_lossy().to_lowercase());
+
+            let format = match get_audio_format(recording.format.or(keep_recording_extension.as_deref().and_then(AudioFormat::from_name)), &config) {
                 Ok(v) => v,
-                Err(_) => {
-                    println!("Required OPENAI_AUTH_KEY environment variable has not been set.");
-                    exit(1);
-                }
+                Err(e) => exit_with_error(e, json),
             };
 
-            if cmd_args.len() != 3 {
-                eprintln!("Expected 3 arguments. Usage: whisper_connector.exe transcribe [language] [audio_device_name]");
-                exit(1);
+            // ffmpeg picks the container from the extension, it has to agree with the codec.
+            if let (Some(path), false) = (&recording.keep_recording, keep_recording_extension.as_deref() == Some(format.extension())) {
+                exit_with_error(format!("\"{path}\" must have the .{} extension of the recording format.", format.extension()), json);
             }
 
-            let language = &cmd_args[1];
+            // A custom filter chain replaces the --denoise one.
+            let audio_filter = match recording.af {
+                Some(v) if !v.trim().is_empty() => Some(v),
+                _ if recording.denoise => Some(DENOISE_AUDIO_FILTER.to_string()),
+                _ => None,
+            };
+
+            // Quoting isn't supported, anything wrong is reported by ffmpeg.
+            let extra_input_args: Vec<String> = recording.ffmpeg_input_args
+                .map(|v| v.split_whitespace().map(|arg| arg.to_string()).collect())
+                .unwrap_or_default();
+
+            let chunk_secs = recording.chunk_secs.or(recording.chunked.then_some(DEFAULT_CHUNK_SECS));
 
-            if language != "en" && language != "pl" {
-                eprintln!("Unknown language, supported languages: 'en', 'pl'.");
-                exit(1);
+            let cost_per_minute = recording.rate
+                .or(recording.cost.then(|| config.cost_per_minute.unwrap_or(DEFAULT_COST_PER_MINUTE)));
+
+            let recording_options = RecordingOptions {
+                max_duration_secs: recording.max_duration,
+                format,
+                hold: recording.hold,
+                show_indicator: std::io::stderr().is_terminal() && !quiet,
+                sample_rate: recording.sample_rate,
+                channels: recording.channels,
+                stop_key: recording.stop_key,
+                dry_run,
+                audio_filter,
+                trim_silence: recording.trim_silence,
+                level_meter: recording.meter,
+                extra_input_args,
+                keep_recording: recording.keep_recording,
+                keep_on_error: recording.keep_on_error,
+                alternative_name: recording.device_alt,
+                // SIGTERM drops the whole command instead, see main.
+                abort_token: None,
+            };
+
+            // Chunks go straight to stdout as they arrive, unless the output is JSON or goes to a file.
+            let stream_chunks = !json && output_file.is_none();
+
+            // Chunks of earlier runs of the session come first, new ones continue their numbering.
+            let resumed_chunks = match &recording.resume {
+                Some(session) => match state::load_session_chunks(session) {
+                    Ok(v) => v,
+                    Err(e) => exit_with_error(e, json),
+                },
+                None => std::collections::BTreeMap::new(),
+            };
+
+            let mut next_session_chunk = resumed_chunks.keys().next_back().map(|v| v + 1).unwrap_or(0);
+
+            if stream_chunks {
+                for text in resumed_chunks.values() {
+                    println!("{text}");
+                }
             }
 
-            let audio_devices = match get_audio_devices().await {
-                Ok(v) => v,
-                Err(e) => {
-                    eprintln!("{e}");
-                    exit(1);
+            let mut print_chunk = |text: &str| {
+                // An unsaved chunk is still printed, only a later --resume would miss it.
+                if let Some(session) = &recording.resume {
+                    if let Err(e) = state::save_session_chunk(session, next_session_chunk, text) {
+                        log::warn!("{e}");
+                    }
+
+                    next_session_chunk += 1;
+                }
+
+                if stream_chunks {
+                    println!("{text}");
                 }
             };
 
-            let audio_device = cmd_args[2].clone();
+            let already_printed = chunk_secs.is_some() && !dry_run;
 
-            if !audio_devices.contains(&audio_device) {
-                eprintln!("This audio device does not exist.");
-                exit(1);
-            }
+            // Each round is a separate recording with its own temporary file.
+            loop {
+                let transcription = match chunk_secs {
+                    Some(chunk_secs) => whisper_connector::transcribe_chunked(
+                        openai_auth_key.clone(),
+                        audio_device.clone(),
+                        chunk_secs,
+                        &recording_options,
+                        &options,
+                        &mut print_chunk,
+                    ).await,
+                    None => whisper_connector::transcribe(openai_auth_key.clone(), audio_device.clone(), &recording_options, &options).await,
+                };
+
+                let transcription = match transcription {
+                    Ok(v) if !resumed_chunks.is_empty() => {
+                        let mut texts: Vec<&str> = resumed_chunks.values().map(|v| v.as_str()).collect();
+
+                        if !v.text.is_empty() {
+                            texts.push(&v.text);
+                        }
+
+                        Transcription { text: texts.join(" "), ..v }
+                    },
+                    Ok(v) => v,
+                    Err(e) => exit_with_whisper_error(e, json),
+                };
+
+                // A loopback device would replace the microphone of the next plain `transcribe`.
+                // Not worth failing a finished transcription over.
+                if !loopback {
+                    if let Err(e) = state::save_last_device(&audio_device) {
+                        log::warn!("{e}");
+                    }
+                }
+
+                // Only an estimate, the API doesn't report what a request actually cost.
+                if let (Some(cost_per_minute), false) = (cost_per_minute, dry_run) {
+                    match transcription.duration_secs {
+                        Some(secs) => eprintln!(
+                            "Transcribed {secs:.1}s of audio, estimated cost ${:.4}",
+                            secs / 60.0 * cost_per_minute
+                        ),
+                        None => eprintln!("Could not estimate the cost, the length of the audio is unknown."),
+                    };
+                }
+
+                if !recording.repeat {
+                    break (transcription, Some(audio_device), None, already_printed);
+                }
+
+                // Ctrl-C or a closed stdin ends the session, the last recording is still printed.
+                let interrupted = transcription.interrupted;
+
+                if args.clipboard && !dry_run {
+                    if let Err(e) = set_clipboard_text(&transcription.text) {
+                        exit_with_error(e, json);
+                    }
+                }
+
+                if !quiet {
+                    print_dropped_segments(transcription.dropped_segments, None);
+                }
+
+                let output = format_transcription(transcription, &options, args.output_format, json, Some(audio_device.clone()), None);
+
+                let output = match &args.post_command {
+                    Some(command) if !dry_run => match run_post_command(command, output).await {
+                        Ok(v) => v,
+                        Err(e) => exit_with_error(e, json),
+                    },
+                    _ => output,
+                };
 
-            match execute_parse_command(openai_auth_key, language.to_string(), audio_device).await {
-                Ok(v) => println!("{}", v),
-                Err(e) => eprintln!("{e}"),
+                if !already_printed {
+                    println!("{output}");
+                }
+
+                // JSON results are one per line already.
+                if !json {
+                    println!("{REPEAT_SEPARATOR}");
+                }
+
+                // Also catches a Ctrl-C while the result was being printed or piped through --post-command.
+                if interrupted || whisper_connector::is_interrupt_requested() {
+                    return;
+                }
             }
-            
         },
-        _ => {
-            println!("Usage:");
-            println!("\twhisper_connector.exe transcribe [language] [audio_device_name]");
-            println!("\twhisper_connector.exe devices");
+    };
+
+    if args.clipboard && !dry_run {
+        if let Err(e) = set_clipboard_text(&transcription.text) {
+            exit_with_error(e, json);
         }
+    }
+
+    if !quiet {
+        print_dropped_segments(transcription.dropped_segments, None);
+    }
+
+    // A dry run has nothing to recognize.
+    let empty = transcription.text.trim().is_empty() && !dry_run;
+
+    let output = format_transcription(transcription, &options, args.output_format, json, audio_device, file_path);
+
+    // A dry run prints the recording path, there is no transcription to process.
+    let output = match &args.post_command {
+        Some(command) if !dry_run => match run_post_command(command, output).await {
+            Ok(v) => v,
+            Err(e) => exit_with_error(e, json),
+        },
+        _ => output,
+    };
+
+    match output_file {
+        Some(path) => {
+            if let Err(e) = tokio::fs::write(&path, output).await {
+                exit_with_error(format!("Failed to write transcription to \"{path}\": {e}"), json);
+            }
+        },
+        // Already printed chunk by chunk.
+        None if already_printed => {},
+        None => println!("{}", output),
+    }
+
+    if args.fail_on_empty && empty {
+        exit_with_code(EMPTY_TRANSCRIPTION_MESSAGE, EXIT_EMPTY, json);
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    // Along with the program name, which clap expects first.
+    let cmd_args: Vec<std::ffi::OsString> = std::env::args_os().collect();
+
+    // clap reads environment variables while parsing, so the .env file has to be loaded first.
+    // Its error is reported once it's known whether it has to be JSON.
+    let env_file_result = load_env_file(get_env_file_arg(&cmd_args));
+
+    let mut command = Cli::command().after_help(get_after_help());
+    let matches = command.clone().try_get_matches_from(&cmd_args);
+
+    // Parse errors happen before --json is known, it's looked up in the raw arguments then.
+    let json = match &matches {
+        Ok(v) => get_json_flag(v),
+        Err(_) => cmd_args.iter().any(|arg| arg == "--json"),
+    };
+
+    if let Err(e) = env_file_result {
+        exit_with_code(e, EXIT_SETUP, json);
+    }
+
+    let matches = match matches {
+        Ok(v) => v,
+        // Only the "error: ..." line, without the hint pointing at --help.
+        Err(e) if json && e.use_stderr() => {
+            let message = e.to_string();
+            exit_with_error(message.lines().next().unwrap_or_default().trim_start_matches("error: "), json);
+        },
+        // clap's own exit code 2 for usage errors would clash with EXIT_SETUP.
+        Err(e) if e.use_stderr() => {
+            let _ = e.print();
+            exit(EXIT_FAILURE);
+        },
+        // --help, which is printed to stdout.
+        Err(e) => e.exit(),
+    };
+
+    let cli = match Cli::from_arg_matches(&matches) {
+        Ok(v) => v,
+        Err(e) => e.exit(),
+    };
+
+    // RUST_LOG is still respected, --verbose is just a shortcut for debug logs of this crate.
+    let default_log_filter = if cli.quiet { "error" } else { "warn" };
+    let mut logger = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_log_filter));
+
+    if cli.verbose {
+        logger.filter_module("whisper_connector", log::LevelFilter::Debug);
+    }
+
+    logger.init();
+
+    if let Some(path) = cli.ffmpeg_path {
+        whisper_connector::set_ffmpeg_path(path);
+    }
+
+    let cli_command = match cli.command {
+        _ if cli.version => Command::Version,
+        Some(v) => v,
+        None => {
+            let _ = command.print_help();
+            return;
+        },
+    };
+
+    let terminate_token = listen_for_sigterm();
+
+    // Dropping the command stops whatever it was doing: ffmpeg is killed and the temporary files
+    // (recordings, audio read from stdin, transcoded files) are removed by their guards.
+    tokio::select! {
+        _ = run_command(cli_command, cli.verbose, cli.quiet) => {},
+        _ = terminate_token.cancelled() => exit_with_code("terminated by SIGTERM", EXIT_TERMINATED, json),
     };
 }
 
+async fn run_command(cli_command: Command, verbose: bool, quiet: bool) {
+    match cli_command {
+        Command::Version => {
+            println!("whisper_connector {}", env!("CARGO_PKG_VERSION"));
+
+            match whisper_connector::get_ffmpeg_version().await {
+                Ok(v) => println!("ffmpeg {v}"),
+                Err(e) => println!("ffmpeg: unavailable ({e})"),
+            }
+        },
+        Command::ClipboardDaemon => run_clipboard_daemon(),
+        Command::Languages => {
+            // Same table the language argument is validated against.
+            for (code, name) in whisper_connector::SUPPORTED_LANGUAGES {
+                println!("{code}\t{name}");
+            }
+        },
+        Command::Doctor { connection } => {
+            if !run_doctor(&connection).await {
+                exit(EXIT_SETUP);
+            }
+        },
+        Command::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "whisper_connector", &mut std::io::stdout());
+        },
+        Command::Devices { loopback, alternative_names, json } => {
+            if let Err(e) = whisper_connector::check_ffmpeg().await {
+                exit_with_whisper_error(e, json);
+            }
+
+            // The index is the number `transcribe` accepts in place of the device name.
+            let devices: Vec<JsonDevice> = if alternative_names {
+                match whisper_connector::list_device_alternative_names().await {
+                    Ok(v) if v.is_empty() => exit_with_code(get_no_devices_message(), EXIT_SETUP, json),
+                    Ok(v) => v.into_iter()
+                        .enumerate()
+                        .map(|(i, (name, alternative_name))| JsonDevice { index: i + 1, name, alternative_name })
+                        .collect(),
+                    Err(e) => exit_with_whisper_error(e, json),
+                }
+            } else {
+                let audio_devices = if loopback {
+                    whisper_connector::list_loopback_devices().await
+                } else {
+                    whisper_connector::list_devices().await
+                };
+
+                match audio_devices {
+                    Ok(v) if v.is_empty() && loopback => exit_with_code(LOOPBACK_HINT, EXIT_SETUP, json),
+                    Ok(v) if v.is_empty() => exit_with_code(get_no_devices_message(), EXIT_SETUP, json),
+                    Ok(v) => v.into_iter()
+                        .enumerate()
+                        .map(|(i, name)| JsonDevice { index: i + 1, name, alternative_name: None })
+                        .collect(),
+                    Err(e) => exit_with_whisper_error(e, json),
+                }
+            };
+
+            if json {
+                match serde_json::to_string(&devices) {
+                    Ok(v) => println!("{v}"),
+                    Err(e) => exit_with_error(e, json),
+                }
+
+                return;
+            }
+
+            for device in devices {
+                println!("{}. {}", device.index, device.name);
+
+                if alternative_names {
+                    println!("   {}", device.alternative_name.as_deref().unwrap_or("(no alternative name)"));
+                }
+            }
+        },
+        Command::Transcribe { language, device, language_flag, recording, transcription } => {
+            // Both positional arguments can be left out when the config file provides them,
+            // a lone argument is treated as the language only if it looks like one.
+            // With --language the only positional argument is the device.
+            let (language, device) = match (language_flag, language, device) {
+                (Some(_), _, Some(_)) => exit_with_error("Pass the language either with --language or as an argument, not both.", transcription.json),
+                (Some(language), device, None) => (Some(language), device),
+                (None, Some(arg), None) if !is_language_arg(&arg) => (None, Some(arg)),
+                (None, language, device) => (get_positional_language(language), device),
+            };
+
+            run_transcription(AudioSource::Device { device_arg: device, recording }, language, false, transcription, verbose, quiet).await;
+        },
+        Command::Translate { device, recording, transcription } => {
+            run_transcription(AudioSource::Device { device_arg: device, recording }, None, true, transcription, verbose, quiet).await;
+        },
+        Command::TranscribeFile { path, language, language_flag, format, transcode, transcription } => {
+            let language = language_flag.or(get_positional_language(language));
+
+            run_transcription(AudioSource::File { path, format, transcode }, language, false, transcription, verbose, quiet).await;
+        },
+        Command::TranscribeDir { dir, language, language_flag, concurrency, rpm, transcription } => {
+            let language = language_flag.or(get_positional_language(language));
+
+            run_transcription(AudioSource::Dir { dir, concurrency, rpm }, language, false, transcription, verbose, quiet).await;
+        },
+    };
+}
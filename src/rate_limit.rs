@@ -0,0 +1,42 @@
+// Client-side pacing of API requests, shared by everything that holds the same TranscriptionOptions.
+//
+// A token bucket holding a single token: requests are spread evenly over the minute instead of
+// being sent in a burst at its start. A 429 pushes the next free slot back for everyone.
+
+use std::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+/// Limits requests to a number per minute, see `TranscriptionOptions::rate_limiter`.
+pub struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_minute: u32) -> RateLimiter {
+        return RateLimiter {
+            interval: Duration::from_secs(60) / requests_per_minute.max(1),
+            next_slot: Mutex::new(Instant::now()),
+        };
+    }
+
+    // Waits for the next free slot, retries of a request need one as well.
+    pub(crate) async fn acquire(&self) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap_or_else(|e| e.into_inner());
+            let slot = (*next_slot).max(Instant::now());
+
+            *next_slot = slot + self.interval;
+            slot
+        };
+
+        tokio::time::sleep_until(slot).await;
+    }
+
+    // The server asked to back off, nobody gets a slot before that has passed.
+    pub(crate) fn pause_for(&self, delay: Duration) {
+        let mut next_slot = self.next_slot.lock().unwrap_or_else(|e| e.into_inner());
+
+        *next_slot = (*next_slot).max(Instant::now() + delay);
+    }
+}
@@ -0,0 +1,106 @@
+// Remembers the last successfully used audio device, so that `transcribe` doesn't ask for it every time.
+// Stored as plain text in the platform data directory, e.g. ~/.local/share/whisper_connector/last_device.
+//
+// Chunked recordings started with --resume keep their chunk texts next to it, in sessions/<session>.jsonl.
+
+fn get_last_device_path() -> Option<std::path::PathBuf> {
+    let project_dirs = directories::ProjectDirs::from("", "GrupaDomanscy", "whisper_connector")?;
+
+    return Some(project_dirs.data_dir().join("last_device"));
+}
+
+// A missing or unreadable file just means there is nothing to reuse.
+pub fn load_last_device() -> Option<String> {
+    let contents = std::fs::read_to_string(get_last_device_path()?).ok()?;
+    let device = contents.trim_end_matches(['\r', '\n']);
+
+    if device.is_empty() {
+        return None;
+    }
+
+    return Some(device.to_string());
+}
+
+pub fn save_last_device(device: &str) -> Result<(), String> {
+    let path = match get_last_device_path() {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+
+    if let Some(dir) = path.parent() {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            return Err(format!("Failed to create \"{}\": {e}", dir.display()));
+        }
+    }
+
+    if let Err(e) = std::fs::write(&path, device) {
+        return Err(format!("Failed to save the last used device to \"{}\": {e}", path.display()));
+    }
+
+    return Ok(());
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct SessionChunk {
+    chunk: usize,
+    text: String,
+}
+
+fn get_session_path(session: &str) -> Option<std::path::PathBuf> {
+    let project_dirs = directories::ProjectDirs::from("", "GrupaDomanscy", "whisper_connector")?;
+
+    return Some(project_dirs.data_dir().join("sessions").join(format!("{session}.jsonl")));
+}
+
+// A line cut off by a crash is skipped, the chunks before it are still there.
+// result: texts of the chunks transcribed so far, keyed by chunk index
+pub fn load_session_chunks(session: &str) -> Result<std::collections::BTreeMap<usize, String>, String> {
+    let path = match get_session_path(session) {
+        Some(v) => v,
+        None => return Ok(std::collections::BTreeMap::new()),
+    };
+
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(v) => v,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(std::collections::BTreeMap::new()),
+        Err(e) => return Err(format!("Failed to read session \"{}\": {e}", path.display())),
+    };
+
+    return Ok(contents.lines()
+        .filter_map(|line| serde_json::from_str::<SessionChunk>(line).ok())
+        .map(|v| (v.chunk, v.text))
+        .collect());
+}
+
+// Appended one line per chunk, so that a crash can't take the earlier chunks with it.
+pub fn save_session_chunk(session: &str, chunk: usize, text: &str) -> Result<(), String> {
+    use std::io::Write;
+
+    let path = match get_session_path(session) {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+
+    if let Some(dir) = path.parent() {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            return Err(format!("Failed to create \"{}\": {e}", dir.display()));
+        }
+    }
+
+    let line = match serde_json::to_string(&SessionChunk { chunk, text: text.to_string() }) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Failed to save chunk {chunk} of the session: {e}")),
+    };
+
+    let result = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| writeln!(file, "{line}"));
+
+    if let Err(e) = result {
+        return Err(format!("Failed to save chunk {chunk} to \"{}\": {e}", path.display()));
+    }
+
+    return Ok(());
+}